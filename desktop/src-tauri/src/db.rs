@@ -1,70 +1,235 @@
+use std::io::Cursor;
+
+use base64::{engine::general_purpose, Engine as _};
 use chrono::Utc;
+use image::imageops::FilterType;
 use rusqlite::{params, Connection, OptionalExtension};
+use sqids::Sqids;
 
-use crate::models::{Patient, PatientInput};
+use crate::models::{AttachmentData, AttachmentInput, AttachmentSummary, Consultation, ConsultationInput, Patient, PatientInput};
 use crate::state::AppError;
 
+/// Thumbnails are capped to this many pixels on the long edge so the patient
+/// view can render previews without loading full-resolution attachments.
+const THUMBNAIL_MAX_SIZE: u32 = 256;
+
 fn apply_key(conn: &Connection, key: &[u8]) -> Result<(), AppError> {
   let key_hex = hex::encode(key);
   let statement = format!("PRAGMA key = \"x'{}'\";", key_hex);
-  conn.execute_batch(&statement)
-    .map_err(|err| AppError::Database(err.to_string()))?;
+  conn.execute_batch(&statement)?;
   Ok(())
 }
 
-pub fn open_connection(db_path: &str, key: &[u8]) -> Result<Connection, AppError> {
-  let conn = Connection::open(db_path)
-    .map_err(|err| AppError::Database(err.to_string()))?;
+pub fn rekey(conn: &Connection, new_key: &[u8]) -> Result<(), AppError> {
+  let key_hex = hex::encode(new_key);
+  let statement = format!("PRAGMA rekey = \"x'{}'\";", key_hex);
+  conn.execute_batch(&statement)?;
+  Ok(())
+}
+
+/// Opens a keyed connection without running migrations, for callers that
+/// already know the schema is current (e.g. a connection pool topping up
+/// after its initial, migrating `open_connection` call).
+pub fn open_keyed_connection(db_path: &str, key: &[u8]) -> Result<Connection, AppError> {
+  let conn = Connection::open(db_path)?;
   apply_key(&conn, key)?;
-  conn.execute_batch("PRAGMA foreign_keys = ON;")
-    .map_err(|err| AppError::Database(err.to_string()))?;
-  init_schema(&conn)?;
+  conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+  Ok(conn)
+}
+
+pub fn open_connection(db_path: &str, key: &[u8]) -> Result<Connection, AppError> {
+  let conn = open_keyed_connection(db_path, key)?;
+  run_migrations(&conn)?;
   Ok(conn)
 }
 
-fn init_schema(conn: &Connection) -> Result<(), AppError> {
-  conn.execute_batch(
-    "CREATE TABLE IF NOT EXISTS patients (
-        id TEXT PRIMARY KEY,
-        first_name TEXT NOT NULL,
-        last_name TEXT NOT NULL,
-        birth_date TEXT NOT NULL,
-        gender TEXT NOT NULL,
-        phone TEXT NOT NULL,
-        email TEXT,
-        created_at TEXT NOT NULL,
-        updated_at TEXT NOT NULL
-      );"
-  ).map_err(|err| AppError::Database(err.to_string()))?;
+/// Ordered schema migrations, applied in sequence. The index of a script in
+/// this array (1-based) is the `PRAGMA user_version` it brings the database
+/// to, so scripts must never be reordered or removed once released.
+const MIGRATIONS: &[&str] = &[
+  // 1: initial schema
+  "CREATE TABLE IF NOT EXISTS patients (
+      id TEXT PRIMARY KEY,
+      first_name TEXT NOT NULL,
+      last_name TEXT NOT NULL,
+      birth_date TEXT NOT NULL,
+      gender TEXT NOT NULL,
+      phone TEXT NOT NULL,
+      email TEXT,
+      created_at TEXT NOT NULL,
+      updated_at TEXT NOT NULL
+    );",
+  // 2: consultation records
+  "CREATE TABLE IF NOT EXISTS consultations (
+      id TEXT PRIMARY KEY,
+      patient_id TEXT NOT NULL,
+      date TEXT NOT NULL,
+      reason TEXT NOT NULL,
+      notes TEXT,
+      created_at TEXT NOT NULL,
+      FOREIGN KEY(patient_id) REFERENCES patients(id) ON DELETE CASCADE
+    );",
+  // 3: patient file attachments
+  "CREATE TABLE IF NOT EXISTS attachments (
+      id TEXT PRIMARY KEY,
+      patient_id TEXT NOT NULL,
+      filename TEXT NOT NULL,
+      mime TEXT NOT NULL,
+      data BLOB NOT NULL,
+      thumbnail BLOB,
+      created_at TEXT NOT NULL,
+      FOREIGN KEY(patient_id) REFERENCES patients(id) ON DELETE CASCADE
+    );",
+  // 4: human-readable patient reference numbers
+  "ALTER TABLE patients ADD COLUMN reference TEXT;
+   CREATE UNIQUE INDEX IF NOT EXISTS idx_patients_reference ON patients(reference);
+   CREATE TABLE IF NOT EXISTS metadata (
+      key TEXT PRIMARY KEY,
+      value TEXT NOT NULL
+    );",
+];
+
+pub fn schema_version(conn: &Connection) -> Result<u32, AppError> {
+  let version = conn.query_row("PRAGMA user_version;", [], |row| row.get(0))?;
+  Ok(version)
+}
+
+/// Number of migrations in `MIGRATIONS`, i.e. the `PRAGMA user_version` a
+/// database ends up at once fully migrated. Lets callers tell a database
+/// that's merely behind (safe to migrate) from one created by a newer build
+/// (ahead of what this binary knows how to read).
+pub fn migration_count() -> u32 {
+  MIGRATIONS.len() as u32
+}
+
+/// Index (0-based) of the migration that added `patients.reference`. Its
+/// `ADD COLUMN` leaves existing rows `NULL`, so that step also needs the
+/// one-time backfill in `backfill_patient_references`.
+const PATIENT_REFERENCE_MIGRATION_INDEX: u32 = 3;
+
+/// Runs every migration whose index is `>= PRAGMA user_version`, each inside
+/// its own transaction. The version pragma is only bumped once a migration's
+/// statements all succeeded, so a failed migration leaves the database on
+/// its previous, consistent version rather than a half-applied one.
+pub fn run_migrations(conn: &Connection) -> Result<(), AppError> {
+  let current = schema_version(conn)?;
+
+  for (index, script) in MIGRATIONS.iter().enumerate() {
+    let index = index as u32;
+    if index < current {
+      continue;
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(script)?;
+    if index == PATIENT_REFERENCE_MIGRATION_INDEX {
+      backfill_patient_references(&tx)?;
+    }
+    tx.execute_batch(&format!("PRAGMA user_version = {};", index + 1))?;
+    tx.commit()?;
+  }
+
   Ok(())
 }
 
+/// Assigns a `reference` to every pre-existing patient row left `NULL` by
+/// the `ADD COLUMN` in migration 4, reusing `generate_reference` so the
+/// `metadata` counter ends up seeded past the highest value handed out —
+/// new patients created after the migration never collide with backfilled ones.
+fn backfill_patient_references(conn: &Connection) -> Result<(), AppError> {
+  let mut stmt = conn.prepare(
+    "SELECT id FROM patients WHERE reference IS NULL ORDER BY created_at ASC"
+  )?;
+  let ids = stmt
+    .query_map([], |row| row.get::<_, String>(0))?
+    .collect::<Result<Vec<_>, _>>()?;
+  drop(stmt);
+
+  for id in ids {
+    let reference = generate_reference(conn)?;
+    conn.execute(
+      "UPDATE patients SET reference = ?1 WHERE id = ?2",
+      params![reference, id],
+    )?;
+  }
+
+  Ok(())
+}
+
+const PATIENT_REFERENCE_COUNTER_KEY: &str = "patient_reference_counter";
+
+/// Atomically bumps the per-profile patient reference counter and returns
+/// the new value. Backed by the `metadata` table rather than `COUNT(*)` so
+/// references stay stable and are never reused, even after deletions.
+fn next_reference_counter(conn: &Connection) -> Result<u64, AppError> {
+  let current: Option<String> = conn.query_row(
+    "SELECT value FROM metadata WHERE key = ?1",
+    params![PATIENT_REFERENCE_COUNTER_KEY],
+    |row| row.get(0),
+  ).optional()?;
+
+  let next = current
+    .and_then(|value| value.parse::<u64>().ok())
+    .unwrap_or(0) + 1;
+
+  conn.execute(
+    "INSERT INTO metadata (key, value) VALUES (?1, ?2)
+     ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    params![PATIENT_REFERENCE_COUNTER_KEY, next.to_string()],
+  )?;
+
+  Ok(next)
+}
+
+/// Alphabet for `generate_reference`'s sqids encoder, restricted to
+/// uppercase letters and digits (no `0`/`O`/`1`/`I` confusables) so the
+/// encoded code is already in its final case. Folding a mixed-case alphabet
+/// to uppercase after the fact would collapse distinct codes that differ
+/// only in letter case into the same string, breaking injectivity.
+const PATIENT_REFERENCE_ALPHABET: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Encodes the next counter value into a short `P-XXXXX` code with a
+/// `sqids` encoder, so references are collision-free and readable without
+/// exposing the underlying sequence.
+fn generate_reference(conn: &Connection) -> Result<String, AppError> {
+  let counter = next_reference_counter(conn)?;
+
+  let sqids = Sqids::builder()
+    .alphabet(PATIENT_REFERENCE_ALPHABET.chars().collect())
+    .min_length(5)
+    .build()
+    .map_err(|err| AppError::Config(err.to_string()))?;
+  let code = sqids.encode(&[counter])
+    .map_err(|err| AppError::Config(err.to_string()))?;
+
+  Ok(format!("P-{}", code))
+}
+
 pub fn list_patients(conn: &Connection) -> Result<Vec<Patient>, AppError> {
   let mut stmt = conn.prepare(
-    "SELECT id, first_name, last_name, birth_date, gender, phone, email, created_at, updated_at
+    "SELECT id, reference, first_name, last_name, birth_date, gender, phone, email, created_at, updated_at
      FROM patients
      ORDER BY created_at DESC"
-  ).map_err(|err| AppError::Database(err.to_string()))?;
+  )?;
 
-  let rows = stmt
-    .query_map([], |row| {
-      Ok(Patient {
-        id: row.get(0)?,
-        first_name: row.get(1)?,
-        last_name: row.get(2)?,
-        birth_date: row.get(3)?,
-        gender: row.get(4)?,
-        phone: row.get(5)?,
-        email: row.get(6)?,
-        created_at: row.get(7)?,
-        updated_at: row.get(8)?,
-      })
+  let rows = stmt.query_map([], |row| {
+    Ok(Patient {
+      id: row.get(0)?,
+      reference: row.get(1)?,
+      first_name: row.get(2)?,
+      last_name: row.get(3)?,
+      birth_date: row.get(4)?,
+      gender: row.get(5)?,
+      phone: row.get(6)?,
+      email: row.get(7)?,
+      created_at: row.get(8)?,
+      updated_at: row.get(9)?,
     })
-    .map_err(|err| AppError::Database(err.to_string()))?;
+  })?;
 
   let mut patients = Vec::new();
   for row in rows {
-    patients.push(row.map_err(|err| AppError::Database(err.to_string()))?);
+    patients.push(row?);
   }
 
   Ok(patients)
@@ -72,13 +237,15 @@ pub fn list_patients(conn: &Connection) -> Result<Vec<Patient>, AppError> {
 
 pub fn create_patient(conn: &Connection, input: PatientInput) -> Result<Patient, AppError> {
   let id = uuid::Uuid::new_v4().to_string();
+  let reference = generate_reference(conn)?;
   let now = Utc::now().to_rfc3339();
 
   conn.execute(
-    "INSERT INTO patients (id, first_name, last_name, birth_date, gender, phone, email, created_at, updated_at)
-     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    "INSERT INTO patients (id, reference, first_name, last_name, birth_date, gender, phone, email, created_at, updated_at)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
     params![
       id,
+      reference,
       input.first_name,
       input.last_name,
       input.birth_date,
@@ -88,7 +255,7 @@ pub fn create_patient(conn: &Connection, input: PatientInput) -> Result<Patient,
       now,
       now,
     ],
-  ).map_err(|err| AppError::Database(err.to_string()))?;
+  )?;
 
   let patient = get_patient(conn, &id)?
     .ok_or_else(|| AppError::Database("Impossible de récupérer le patient".into()))?;
@@ -97,30 +264,259 @@ pub fn create_patient(conn: &Connection, input: PatientInput) -> Result<Patient,
 }
 
 pub fn delete_patient(conn: &Connection, patient_id: &str) -> Result<(), AppError> {
-  conn.execute(
-    "DELETE FROM patients WHERE id = ?1",
-    params![patient_id],
-  ).map_err(|err| AppError::Database(err.to_string()))?;
+  conn.execute("DELETE FROM patients WHERE id = ?1", params![patient_id])?;
   Ok(())
 }
 
 fn get_patient(conn: &Connection, patient_id: &str) -> Result<Option<Patient>, AppError> {
-  conn.query_row(
-    "SELECT id, first_name, last_name, birth_date, gender, phone, email, created_at, updated_at
+  let patient = conn.query_row(
+    "SELECT id, reference, first_name, last_name, birth_date, gender, phone, email, created_at, updated_at
      FROM patients WHERE id = ?1",
     params![patient_id],
     |row| {
       Ok(Patient {
         id: row.get(0)?,
-        first_name: row.get(1)?,
-        last_name: row.get(2)?,
-        birth_date: row.get(3)?,
-        gender: row.get(4)?,
-        phone: row.get(5)?,
-        email: row.get(6)?,
-        created_at: row.get(7)?,
-        updated_at: row.get(8)?,
+        reference: row.get(1)?,
+        first_name: row.get(2)?,
+        last_name: row.get(3)?,
+        birth_date: row.get(4)?,
+        gender: row.get(5)?,
+        phone: row.get(6)?,
+        email: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
       })
     }
-  ).optional().map_err(|err| AppError::Database(err.to_string()))
+  ).optional()?;
+  Ok(patient)
+}
+
+pub fn find_patient_by_reference(conn: &Connection, reference: &str) -> Result<Option<Patient>, AppError> {
+  let patient = conn.query_row(
+    "SELECT id, reference, first_name, last_name, birth_date, gender, phone, email, created_at, updated_at
+     FROM patients WHERE reference = ?1",
+    params![reference],
+    |row| {
+      Ok(Patient {
+        id: row.get(0)?,
+        reference: row.get(1)?,
+        first_name: row.get(2)?,
+        last_name: row.get(3)?,
+        birth_date: row.get(4)?,
+        gender: row.get(5)?,
+        phone: row.get(6)?,
+        email: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+      })
+    }
+  ).optional()?;
+  Ok(patient)
+}
+
+/// Decodes an image and downscales it to fit `THUMBNAIL_MAX_SIZE` on the
+/// long edge, returning `None` when the bytes don't decode as an image so
+/// callers can store attachments without a preview rather than fail outright.
+fn generate_thumbnail(data: &[u8]) -> Option<Vec<u8>> {
+  let decoded = image::load_from_memory(data).ok()?;
+  let thumbnail = if decoded.width() > THUMBNAIL_MAX_SIZE || decoded.height() > THUMBNAIL_MAX_SIZE {
+    decoded.resize(THUMBNAIL_MAX_SIZE, THUMBNAIL_MAX_SIZE, FilterType::Triangle)
+  } else {
+    decoded
+  };
+
+  let mut buf = Vec::new();
+  thumbnail.write_to(&mut Cursor::new(&mut buf), image::ImageFormat::Png).ok()?;
+  Some(buf)
+}
+
+pub fn list_consultations(conn: &Connection, patient_id: &str) -> Result<Vec<Consultation>, AppError> {
+  let mut stmt = conn.prepare(
+    "SELECT id, patient_id, date, reason, notes, created_at
+     FROM consultations
+     WHERE patient_id = ?1
+     ORDER BY date DESC"
+  )?;
+
+  let rows = stmt.query_map(params![patient_id], |row| {
+    Ok(Consultation {
+      id: row.get(0)?,
+      patient_id: row.get(1)?,
+      date: row.get(2)?,
+      reason: row.get(3)?,
+      notes: row.get(4)?,
+      created_at: row.get(5)?,
+    })
+  })?;
+
+  let mut consultations = Vec::new();
+  for row in rows {
+    consultations.push(row?);
+  }
+
+  Ok(consultations)
+}
+
+pub fn create_consultation(conn: &Connection, input: ConsultationInput) -> Result<Consultation, AppError> {
+  let id = uuid::Uuid::new_v4().to_string();
+  let now = Utc::now().to_rfc3339();
+
+  conn.execute(
+    "INSERT INTO consultations (id, patient_id, date, reason, notes, created_at)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    params![
+      id,
+      input.patient_id,
+      input.date,
+      input.reason,
+      input.notes,
+      now,
+    ],
+  )?;
+
+  let consultation = get_consultation(conn, &id)?
+    .ok_or_else(|| AppError::Database("Impossible de récupérer la consultation".into()))?;
+
+  Ok(consultation)
+}
+
+pub fn delete_consultation(conn: &Connection, consultation_id: &str) -> Result<(), AppError> {
+  conn.execute("DELETE FROM consultations WHERE id = ?1", params![consultation_id])?;
+  Ok(())
+}
+
+fn get_consultation(conn: &Connection, consultation_id: &str) -> Result<Option<Consultation>, AppError> {
+  let consultation = conn.query_row(
+    "SELECT id, patient_id, date, reason, notes, created_at
+     FROM consultations WHERE id = ?1",
+    params![consultation_id],
+    |row| {
+      Ok(Consultation {
+        id: row.get(0)?,
+        patient_id: row.get(1)?,
+        date: row.get(2)?,
+        reason: row.get(3)?,
+        notes: row.get(4)?,
+        created_at: row.get(5)?,
+      })
+    }
+  ).optional()?;
+  Ok(consultation)
+}
+
+pub fn add_attachment(conn: &Connection, input: AttachmentInput) -> Result<AttachmentSummary, AppError> {
+  let id = uuid::Uuid::new_v4().to_string();
+  let now = Utc::now().to_rfc3339();
+
+  let data = general_purpose::STANDARD
+    .decode(&input.data_b64)
+    .map_err(|err| AppError::Validation(err.to_string()))?;
+  let mime = mime_guess::from_path(&input.filename)
+    .first_or_octet_stream()
+    .to_string();
+  let thumbnail = generate_thumbnail(&data);
+
+  conn.execute(
+    "INSERT INTO attachments (id, patient_id, filename, mime, data, thumbnail, created_at)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    params![id, input.patient_id, input.filename, mime, data, thumbnail, now],
+  )?;
+
+  Ok(AttachmentSummary {
+    id,
+    patient_id: input.patient_id,
+    filename: input.filename,
+    mime,
+    created_at: now,
+    thumbnail_b64: thumbnail.map(|bytes| general_purpose::STANDARD.encode(bytes)),
+  })
+}
+
+pub fn list_attachments(conn: &Connection, patient_id: &str) -> Result<Vec<AttachmentSummary>, AppError> {
+  let mut stmt = conn.prepare(
+    "SELECT id, patient_id, filename, mime, thumbnail, created_at
+     FROM attachments
+     WHERE patient_id = ?1
+     ORDER BY created_at DESC"
+  )?;
+
+  let rows = stmt.query_map(params![patient_id], |row| {
+    let thumbnail: Option<Vec<u8>> = row.get(4)?;
+    Ok(AttachmentSummary {
+      id: row.get(0)?,
+      patient_id: row.get(1)?,
+      filename: row.get(2)?,
+      mime: row.get(3)?,
+      thumbnail_b64: thumbnail.map(|bytes| general_purpose::STANDARD.encode(bytes)),
+      created_at: row.get(5)?,
+    })
+  })?;
+
+  let mut attachments = Vec::new();
+  for row in rows {
+    attachments.push(row?);
+  }
+
+  Ok(attachments)
+}
+
+pub fn get_attachment(conn: &Connection, attachment_id: &str) -> Result<Option<AttachmentData>, AppError> {
+  let attachment = conn.query_row(
+    "SELECT filename, mime, data
+     FROM attachments WHERE id = ?1",
+    params![attachment_id],
+    |row| {
+      let filename: String = row.get(0)?;
+      let mime: String = row.get(1)?;
+      let data: Vec<u8> = row.get(2)?;
+      Ok(AttachmentData {
+        filename,
+        mime,
+        data_b64: general_purpose::STANDARD.encode(data),
+      })
+    }
+  ).optional()?;
+  Ok(attachment)
+}
+
+pub fn delete_attachment(conn: &Connection, attachment_id: &str) -> Result<(), AppError> {
+  conn.execute("DELETE FROM attachments WHERE id = ?1", params![attachment_id])?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn migrates_v1_database_with_existing_patients() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+
+    // Simulate a database created before the reference column existed.
+    conn.execute_batch(MIGRATIONS[0]).unwrap();
+    conn.execute_batch("PRAGMA user_version = 1;").unwrap();
+
+    conn.execute(
+      "INSERT INTO patients (id, first_name, last_name, birth_date, gender, phone, email, created_at, updated_at)
+       VALUES ('p1', 'Jean', 'Dupont', '1990-01-01', 'M', '0102030405', NULL, '2024-01-01T00:00:00Z', '2024-01-01T00:00:00Z')",
+      [],
+    ).unwrap();
+
+    run_migrations(&conn).unwrap();
+
+    let patients = list_patients(&conn).unwrap();
+    assert_eq!(patients.len(), 1);
+    assert!(patients[0].reference.starts_with("P-"));
+
+    let created = create_patient(&conn, PatientInput {
+      first_name: "Marie".into(),
+      last_name: "Martin".into(),
+      birth_date: "1985-05-05".into(),
+      gender: "F".into(),
+      phone: "0607080910".into(),
+      email: None,
+    }).unwrap();
+    assert_ne!(created.reference, patients[0].reference);
+  }
 }
@@ -1,12 +1,22 @@
 mod db;
 mod models;
+mod pool;
 mod profile;
 mod state;
 
+use std::sync::Arc;
+
 use tauri::State;
 
-use crate::models::{Patient, PatientInput, ProfileSummary};
-use crate::profile::{create_profile as create_profile_record, derive_key, load_profiles, verify_password};
+use crate::models::{
+  AttachmentData, AttachmentInput, AttachmentSummary, Consultation, ConsultationInput, Patient,
+  PatientInput, ProfileSummary, SchemaVersion,
+};
+use crate::pool::ConnectionPool;
+use crate::profile::{
+  change_password as change_password_record, create_profile as create_profile_record, derive_key,
+  load_profiles, verify_password,
+};
 use crate::state::{ActiveProfile, AppError, AppState};
 
 fn get_active_profile(state: &State<AppState>) -> Result<ActiveProfile, AppError> {
@@ -14,6 +24,11 @@ fn get_active_profile(state: &State<AppState>) -> Result<ActiveProfile, AppError
   guard.clone().ok_or_else(|| AppError::Auth("Aucun profil ouvert".into()))
 }
 
+fn get_pool(state: &State<AppState>) -> Result<Arc<ConnectionPool>, AppError> {
+  let guard = state.pool.lock().map_err(|_| AppError::Config("Verrouillage impossible".into()))?;
+  guard.clone().ok_or_else(|| AppError::Auth("Aucun profil ouvert".into()))
+}
+
 #[tauri::command]
 fn list_profiles(app: tauri::AppHandle) -> Result<Vec<ProfileSummary>, String> {
   load_profiles(&app)
@@ -48,15 +63,53 @@ fn open_profile(
   verify_password(&profile, &password).map_err(String::from)?;
   let key = derive_key(&profile, &password).map_err(String::from)?;
 
-  let conn = db::open_connection(&profile.db_path, &key).map_err(String::from)?;
-  drop(conn);
-
   let mut guard = state.active_profile.lock().map_err(|_| AppError::Config("Verrouillage impossible".into()))?;
   *guard = Some(ActiveProfile {
     id: profile.id.clone(),
     db_path: profile.db_path.clone(),
-    key,
+    key: key.clone(),
   });
+  drop(guard);
+
+  // Re-opening the already-active profile reuses its pool instead of
+  // dropping and rebuilding it, since the key can only have changed via
+  // change_password, which replaces the pool itself.
+  let mut pool_guard = state.pool.lock().map_err(|_| AppError::Config("Verrouillage impossible".into()))?;
+  let already_open = pool_guard.as_ref().map(|pool| pool.profile_id() == profile.id).unwrap_or(false);
+  if !already_open {
+    let conn_pool = ConnectionPool::new(&profile.id, &profile.db_path, &key).map_err(String::from)?;
+    *pool_guard = Some(Arc::new(conn_pool));
+  }
+
+  Ok(profile.summary())
+}
+
+#[tauri::command]
+fn change_password(
+  app: tauri::AppHandle,
+  state: State<AppState>,
+  profile_id: String,
+  old_password: String,
+  new_password: String,
+) -> Result<ProfileSummary, String> {
+  let (profile, new_key) = change_password_record(&app, &profile_id, &old_password, &new_password)
+    .map_err(String::from)?;
+
+  let mut guard = state.active_profile.lock()
+    .map_err(|_| AppError::Config("Verrouillage impossible".into()))?;
+  let is_active = guard.as_ref().map(|active| active.id == profile.id).unwrap_or(false);
+  if let Some(active) = guard.as_mut() {
+    if is_active {
+      active.key = new_key.clone();
+    }
+  }
+  drop(guard);
+
+  if is_active {
+    let conn_pool = ConnectionPool::new(&profile.id, &profile.db_path, &new_key).map_err(String::from)?;
+    let mut pool_guard = state.pool.lock().map_err(|_| AppError::Config("Verrouillage impossible".into()))?;
+    *pool_guard = Some(Arc::new(conn_pool));
+  }
 
   Ok(profile.summary())
 }
@@ -64,24 +117,100 @@ fn open_profile(
 #[tauri::command]
 fn list_patients(state: State<AppState>) -> Result<Vec<Patient>, String> {
   let active = get_active_profile(&state).map_err(String::from)?;
-  let conn = db::open_connection(&active.db_path, &active.key).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
   db::list_patients(&conn).map_err(String::from)
 }
 
 #[tauri::command]
 fn create_patient(state: State<AppState>, patient: PatientInput) -> Result<Patient, String> {
   let active = get_active_profile(&state).map_err(String::from)?;
-  let conn = db::open_connection(&active.db_path, &active.key).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
   db::create_patient(&conn, patient).map_err(String::from)
 }
 
 #[tauri::command]
 fn delete_patient(state: State<AppState>, patient_id: String) -> Result<(), String> {
   let active = get_active_profile(&state).map_err(String::from)?;
-  let conn = db::open_connection(&active.db_path, &active.key).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
   db::delete_patient(&conn, &patient_id).map_err(String::from)
 }
 
+#[tauri::command]
+fn list_consultations(state: State<AppState>, patient_id: String) -> Result<Vec<Consultation>, String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  db::list_consultations(&conn, &patient_id).map_err(String::from)
+}
+
+#[tauri::command]
+fn create_consultation(state: State<AppState>, consultation: ConsultationInput) -> Result<Consultation, String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  db::create_consultation(&conn, consultation).map_err(String::from)
+}
+
+#[tauri::command]
+fn delete_consultation(state: State<AppState>, consultation_id: String) -> Result<(), String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  db::delete_consultation(&conn, &consultation_id).map_err(String::from)
+}
+
+#[tauri::command]
+fn find_patient_by_reference(state: State<AppState>, reference: String) -> Result<Option<Patient>, String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  db::find_patient_by_reference(&conn, &reference).map_err(String::from)
+}
+
+#[tauri::command]
+fn add_attachment(state: State<AppState>, attachment: AttachmentInput) -> Result<AttachmentSummary, String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  db::add_attachment(&conn, attachment).map_err(String::from)
+}
+
+#[tauri::command]
+fn list_attachments(state: State<AppState>, patient_id: String) -> Result<Vec<AttachmentSummary>, String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  db::list_attachments(&conn, &patient_id).map_err(String::from)
+}
+
+#[tauri::command]
+fn get_attachment(state: State<AppState>, attachment_id: String) -> Result<Option<AttachmentData>, String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  db::get_attachment(&conn, &attachment_id).map_err(String::from)
+}
+
+#[tauri::command]
+fn delete_attachment(state: State<AppState>, attachment_id: String) -> Result<(), String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  db::delete_attachment(&conn, &attachment_id).map_err(String::from)
+}
+
+#[tauri::command]
+fn schema_version(state: State<AppState>) -> Result<SchemaVersion, String> {
+  let active = get_active_profile(&state).map_err(String::from)?;
+  let pool = get_pool(&state).map_err(String::from)?;
+  let conn = pool.checkout(&active.db_path, &active.key).map_err(String::from)?;
+  let current = db::schema_version(&conn).map_err(String::from)?;
+  Ok(SchemaVersion { current, latest: db::migration_count() })
+}
+
 fn main() {
   tauri::Builder::default()
     .manage(AppState::default())
@@ -89,9 +218,19 @@ fn main() {
       list_profiles,
       create_profile,
       open_profile,
+      change_password,
       list_patients,
       create_patient,
       delete_patient,
+      find_patient_by_reference,
+      list_consultations,
+      create_consultation,
+      delete_consultation,
+      add_attachment,
+      list_attachments,
+      get_attachment,
+      delete_attachment,
+      schema_version,
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
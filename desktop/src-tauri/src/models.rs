@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SchemaVersion {
+  pub current: u32,
+  pub latest: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ProfileSummary {
   pub id: String,
@@ -10,6 +16,7 @@ pub struct ProfileSummary {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Patient {
   pub id: String,
+  pub reference: String,
   pub first_name: String,
   pub last_name: String,
   pub birth_date: String,
@@ -29,3 +36,45 @@ pub struct PatientInput {
   pub phone: String,
   pub email: Option<String>,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Consultation {
+  pub id: String,
+  pub patient_id: String,
+  pub date: String,
+  pub reason: String,
+  pub notes: Option<String>,
+  pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConsultationInput {
+  pub patient_id: String,
+  pub date: String,
+  pub reason: String,
+  pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentSummary {
+  pub id: String,
+  pub patient_id: String,
+  pub filename: String,
+  pub mime: String,
+  pub created_at: String,
+  pub thumbnail_b64: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentInput {
+  pub patient_id: String,
+  pub filename: String,
+  pub data_b64: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentData {
+  pub filename: String,
+  pub mime: String,
+  pub data_b64: String,
+}
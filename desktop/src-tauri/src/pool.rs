@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+
+use rusqlite::Connection;
+
+use crate::db;
+use crate::state::AppError;
+
+const POOL_SIZE: usize = 4;
+
+/// A small pool of already-keyed SQLCipher connections for a single profile,
+/// modeled after `deadpool-sync`'s checkout/recycle pattern but kept plainly
+/// synchronous since Tauri commands here run on their own worker thread.
+/// `new` eagerly opens `POOL_SIZE` connections via `db::open_connection`,
+/// which is also the only place `run_migrations` runs. Idle checkouts reuse
+/// those already-migrated connections as-is; a checkout that overflows the
+/// pool still has to open a fresh SQLCipher connection (every connection
+/// needs its own `PRAGMA key`, regardless of pooling), so it calls
+/// `db::open_keyed_connection` to apply the key without re-running
+/// migrations against an already-current schema.
+pub struct ConnectionPool {
+  profile_id: String,
+  idle: Mutex<Vec<Connection>>,
+}
+
+impl ConnectionPool {
+  pub fn new(profile_id: &str, db_path: &str, key: &[u8]) -> Result<Self, AppError> {
+    let mut idle = Vec::with_capacity(POOL_SIZE);
+    for _ in 0..POOL_SIZE {
+      idle.push(db::open_connection(db_path, key)?);
+    }
+
+    Ok(Self {
+      profile_id: profile_id.to_string(),
+      idle: Mutex::new(idle),
+    })
+  }
+
+  pub fn profile_id(&self) -> &str {
+    &self.profile_id
+  }
+
+  /// Checks out an idle connection, opening a fresh one if the pool has run
+  /// dry (e.g. under concurrent commands) rather than blocking the caller.
+  /// The fallback still re-applies `PRAGMA key` like any new connection, but
+  /// skips migrations since `new` already brought the schema up to date.
+  pub fn checkout(&self, db_path: &str, key: &[u8]) -> Result<PooledConnection<'_>, AppError> {
+    let mut idle = self.idle.lock()
+      .map_err(|_| AppError::Config("Verrouillage impossible".into()))?;
+
+    let conn = match idle.pop() {
+      Some(conn) => conn,
+      None => db::open_keyed_connection(db_path, key)?,
+    };
+
+    Ok(PooledConnection { pool: self, conn: Some(conn) })
+  }
+
+  fn release(&self, conn: Connection) {
+    if let Ok(mut idle) = self.idle.lock() {
+      idle.push(conn);
+    }
+  }
+}
+
+pub struct PooledConnection<'a> {
+  pool: &'a ConnectionPool,
+  conn: Option<Connection>,
+}
+
+impl<'a> std::ops::Deref for PooledConnection<'a> {
+  type Target = Connection;
+
+  fn deref(&self) -> &Connection {
+    self.conn.as_ref().expect("connection checked out")
+  }
+}
+
+impl<'a> Drop for PooledConnection<'a> {
+  fn drop(&mut self) {
+    if let Some(conn) = self.conn.take() {
+      self.pool.release(conn);
+    }
+  }
+}
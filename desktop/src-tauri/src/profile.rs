@@ -10,6 +10,7 @@ use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use tauri::AppHandle;
 
+use crate::db;
 use crate::models::ProfileSummary;
 use crate::state::AppError;
 
@@ -56,7 +57,7 @@ fn profiles_file_path(app: &AppHandle) -> Result<PathBuf, AppError> {
 
 fn ensure_parent(path: &Path) -> Result<(), AppError> {
   if let Some(parent) = path.parent() {
-    fs::create_dir_all(parent).map_err(|err| AppError::Io(err.to_string()))?;
+    fs::create_dir_all(parent)?;
   }
   Ok(())
 }
@@ -66,20 +67,46 @@ pub fn load_profiles(app: &AppHandle) -> Result<Vec<StoredProfile>, AppError> {
   if !path.exists() {
     return Ok(Vec::new());
   }
-  let content = fs::read_to_string(&path).map_err(|err| AppError::Io(err.to_string()))?;
-  let profiles = serde_json::from_str::<Vec<StoredProfile>>(&content)
-    .map_err(|err| AppError::Io(err.to_string()))?;
+  let content = fs::read_to_string(&path)?;
+  let profiles = serde_json::from_str::<Vec<StoredProfile>>(&content)?;
   Ok(profiles)
 }
 
 pub fn save_profiles(app: &AppHandle, profiles: &[StoredProfile]) -> Result<(), AppError> {
   let path = profiles_file_path(app)?;
   ensure_parent(&path)?;
-  let data = serde_json::to_string_pretty(profiles).map_err(|err| AppError::Io(err.to_string()))?;
-  fs::write(&path, data).map_err(|err| AppError::Io(err.to_string()))?;
+  let data = serde_json::to_string_pretty(profiles)?;
+  fs::write(&path, data)?;
   Ok(())
 }
 
+fn default_argon2_params() -> Argon2ParamsConfig {
+  Argon2ParamsConfig {
+    m_cost: 19456,
+    t_cost: 2,
+    p_cost: 1,
+    output_len: 32,
+  }
+}
+
+fn generate_key_salt() -> String {
+  let mut salt_bytes = [0u8; 16];
+  OsRng.fill_bytes(&mut salt_bytes);
+  general_purpose::STANDARD.encode(salt_bytes)
+}
+
+fn hash_password(password: &str, params: &Argon2ParamsConfig) -> Result<String, AppError> {
+  let argon2_params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(params.output_len as usize))
+    .map_err(|err| AppError::Config(err.to_string()))?;
+
+  let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params);
+
+  let salt = SaltString::generate(&mut OsRng);
+  let password_hash = argon2.hash_password(password.as_bytes(), &salt)?.to_string();
+
+  Ok(password_hash)
+}
+
 pub fn create_profile(app: &AppHandle, name: &str, password: &str) -> Result<StoredProfile, AppError> {
   if name.trim().is_empty() {
     return Err(AppError::Validation("Le nom du profil est requis".into()));
@@ -93,30 +120,12 @@ pub fn create_profile(app: &AppHandle, name: &str, password: &str) -> Result<Sto
   let id = uuid::Uuid::new_v4().to_string();
   let created_at = Utc::now().to_rfc3339();
 
-  let mut salt_bytes = [0u8; 16];
-  OsRng.fill_bytes(&mut salt_bytes);
-  let key_salt_b64 = general_purpose::STANDARD.encode(salt_bytes);
-
-  let params = Argon2ParamsConfig {
-    m_cost: 19456,
-    t_cost: 2,
-    p_cost: 1,
-    output_len: 32,
-  };
-
-  let argon2_params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(params.output_len as usize))
-    .map_err(|err| AppError::Config(err.to_string()))?;
-
-  let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params);
-
-  let salt = SaltString::generate(&mut OsRng);
-  let password_hash = argon2
-    .hash_password(password.as_bytes(), &salt)
-    .map_err(|err| AppError::Config(err.to_string()))?
-    .to_string();
+  let key_salt_b64 = generate_key_salt();
+  let params = default_argon2_params();
+  let password_hash = hash_password(password, &params)?;
 
   let profile_dir = app_data_dir(app)?.join("profiles").join(&id);
-  fs::create_dir_all(&profile_dir).map_err(|err| AppError::Io(err.to_string()))?;
+  fs::create_dir_all(&profile_dir)?;
   let db_path = profile_dir.join("profile.db");
 
   let stored = StoredProfile {
@@ -136,8 +145,7 @@ pub fn create_profile(app: &AppHandle, name: &str, password: &str) -> Result<Sto
 }
 
 pub fn verify_password(profile: &StoredProfile, password: &str) -> Result<(), AppError> {
-  let parsed_hash = PasswordHash::new(&profile.password_hash)
-    .map_err(|err| AppError::Config(err.to_string()))?;
+  let parsed_hash = PasswordHash::new(&profile.password_hash)?;
 
   let params = Params::new(
     profile.argon2_params.m_cost,
@@ -173,3 +181,51 @@ pub fn derive_key(profile: &StoredProfile, password: &str) -> Result<Vec<u8>, Ap
 
   Ok(key)
 }
+
+/// Changes a profile's password, re-encrypting its database with a freshly
+/// derived key via SQLCipher's `PRAGMA rekey`. The verification hash and the
+/// encryption key are rotated together so a stale one can never outlive the
+/// other. The profile record is persisted *before* the rekey runs, so if
+/// `save_profiles` fails the database is untouched and still opens with the
+/// old password, rather than leaving a rekeyed database whose stored salt
+/// and hash still point at the old key. Returns the updated `StoredProfile`
+/// and the new key so the caller can refresh any `ActiveProfile` held in
+/// `AppState`.
+pub fn change_password(
+  app: &AppHandle,
+  profile_id: &str,
+  old_password: &str,
+  new_password: &str,
+) -> Result<(StoredProfile, Vec<u8>), AppError> {
+  if new_password.trim().is_empty() {
+    return Err(AppError::Validation("Le mot de passe est requis".into()));
+  }
+
+  let mut profiles = load_profiles(app)?;
+  let index = profiles
+    .iter()
+    .position(|profile| profile.id == profile_id)
+    .ok_or_else(|| AppError::NotFound("Profil introuvable".into()))?;
+
+  verify_password(&profiles[index], old_password)?;
+  let old_key = derive_key(&profiles[index], old_password)?;
+
+  let new_key_salt_b64 = generate_key_salt();
+  let params = default_argon2_params();
+  let new_password_hash = hash_password(new_password, &params)?;
+
+  let mut updated = profiles[index].clone();
+  updated.key_salt_b64 = new_key_salt_b64;
+  updated.argon2_params = params;
+  let new_key = derive_key(&updated, new_password)?;
+  updated.password_hash = new_password_hash;
+
+  profiles[index] = updated.clone();
+  save_profiles(app, &profiles)?;
+
+  let conn = db::open_connection(&updated.db_path, &old_key)?;
+  db::rekey(&conn, &new_key)?;
+  drop(conn);
+
+  Ok((updated, new_key))
+}
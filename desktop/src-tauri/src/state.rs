@@ -1,7 +1,9 @@
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use serde::Serialize;
 
+use crate::pool::ConnectionPool;
+
 #[derive(Debug, Serialize)]
 pub struct ActiveProfile {
   pub id: String,
@@ -12,6 +14,7 @@ pub struct ActiveProfile {
 #[derive(Default)]
 pub struct AppState {
   pub active_profile: Mutex<Option<ActiveProfile>>,
+  pub pool: Mutex<Option<Arc<ConnectionPool>>>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -35,3 +38,27 @@ impl From<AppError> for String {
     value.to_string()
   }
 }
+
+impl From<std::io::Error> for AppError {
+  fn from(err: std::io::Error) -> Self {
+    AppError::Io(err.to_string())
+  }
+}
+
+impl From<rusqlite::Error> for AppError {
+  fn from(err: rusqlite::Error) -> Self {
+    AppError::Database(err.to_string())
+  }
+}
+
+impl From<serde_json::Error> for AppError {
+  fn from(err: serde_json::Error) -> Self {
+    AppError::Io(err.to_string())
+  }
+}
+
+impl From<argon2::password_hash::Error> for AppError {
+  fn from(err: argon2::password_hash::Error) -> Self {
+    AppError::Config(err.to_string())
+  }
+}